@@ -0,0 +1,196 @@
+//! Authenticated key exchange and per-frame encryption for control sessions.
+//!
+//! Handshake: each side sends an ephemeral X25519 public key, its static
+//! ed25519 public key, and a signature over the ephemeral key. Both sides
+//! verify the signature, run X25519 ECDH, and feed the shared secret through
+//! HKDF-SHA256 to derive two ChaCha20-Poly1305 keys (one per direction, so
+//! the two ends never reuse a nonce against the same key). Every frame after
+//! the handshake is sealed with a monotonically increasing 64-bit nonce
+//! counter; a counter that would wrap, or any AEAD/signature failure, tears
+//! the session down rather than risk nonce reuse.
+
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use hkdf::Hkdf;
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey as XPublicKey};
+
+#[derive(Serialize, Deserialize)]
+struct HandshakeMsg {
+    eph_pub: [u8; 32],
+    static_pub: [u8; 32],
+    sig: [u8; 64],
+}
+
+/// Which side of the handshake we are; determines which derived key is used
+/// to send vs. receive so the two directions never share a nonce space.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Initiator,
+    Responder,
+}
+
+pub struct SecureChannel {
+    send_key: ChaCha20Poly1305,
+    recv_key: ChaCha20Poly1305,
+    send_counter: u64,
+    recv_counter: u64,
+    pub peer_fingerprint: String,
+}
+
+impl SecureChannel {
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        if self.send_counter == u64::MAX {
+            anyhow::bail!("nonce counter exhausted; session must be re-established");
+        }
+        let nonce = nonce_from_counter(self.send_counter);
+        self.send_counter += 1;
+        self.send_key
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| anyhow::anyhow!("encryption failure"))
+    }
+
+    pub fn decrypt(&mut self, ciphertext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        if self.recv_counter == u64::MAX {
+            anyhow::bail!("nonce counter exhausted; session must be re-established");
+        }
+        let nonce = nonce_from_counter(self.recv_counter);
+        self.recv_counter += 1;
+        self.recv_key
+            .decrypt(Nonce::from_slice(&nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("AEAD authentication failure"))
+    }
+}
+
+fn nonce_from_counter(counter: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+/// Run the mutual handshake over an already-established transport, using the
+/// supplied `send`/`recv` closures to exchange the two handshake frames.
+/// Returns the derived channel, with the peer's fingerprint for the caller
+/// to check against the trust store.
+pub async fn handshake<S, R, SFut, RFut>(
+    role: Role,
+    identity: &SigningKey,
+    mut send: S,
+    mut recv: R,
+) -> anyhow::Result<SecureChannel>
+where
+    S: FnMut(Vec<u8>) -> SFut,
+    SFut: std::future::Future<Output = anyhow::Result<()>>,
+    R: FnMut() -> RFut,
+    RFut: std::future::Future<Output = anyhow::Result<Vec<u8>>>,
+{
+    let eph_secret = EphemeralSecret::random_from_rng(OsRng);
+    let eph_pub = XPublicKey::from(&eph_secret);
+    let static_pub = identity.verifying_key();
+    let sig: Signature = identity.sign(eph_pub.as_bytes());
+
+    let ours = HandshakeMsg {
+        eph_pub: *eph_pub.as_bytes(),
+        static_pub: static_pub.to_bytes(),
+        sig: sig.to_bytes(),
+    };
+    send(serde_json::to_vec(&ours)?).await?;
+    let theirs: HandshakeMsg = serde_json::from_slice(&recv().await?)?;
+
+    let peer_static = VerifyingKey::from_bytes(&theirs.static_pub)?;
+    let peer_sig = Signature::from_bytes(&theirs.sig);
+    peer_static
+        .verify(&theirs.eph_pub, &peer_sig)
+        .map_err(|_| anyhow::anyhow!("handshake signature verification failed"))?;
+
+    let peer_eph = XPublicKey::from(theirs.eph_pub);
+    let shared = eph_secret.diffie_hellman(&peer_eph);
+
+    let hk = Hkdf::<Sha256>::new(None, shared.as_bytes());
+    let mut initiator_to_responder = [0u8; 32];
+    let mut responder_to_initiator = [0u8; 32];
+    hk.expand(b"controll initiator->responder", &mut initiator_to_responder)
+        .map_err(|_| anyhow::anyhow!("HKDF expand failed"))?;
+    hk.expand(b"controll responder->initiator", &mut responder_to_initiator)
+        .map_err(|_| anyhow::anyhow!("HKDF expand failed"))?;
+
+    let (send_key_bytes, recv_key_bytes) = match role {
+        Role::Initiator => (initiator_to_responder, responder_to_initiator),
+        Role::Responder => (responder_to_initiator, initiator_to_responder),
+    };
+
+    Ok(SecureChannel {
+        send_key: ChaCha20Poly1305::new((&send_key_bytes).into()),
+        recv_key: ChaCha20Poly1305::new((&recv_key_bytes).into()),
+        send_counter: 0,
+        recv_counter: 0,
+        peer_fingerprint: crate::identity::fingerprint_of(&peer_static),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel_pair() -> (SecureChannel, SecureChannel) {
+        let key_a = [1u8; 32];
+        let key_b = [2u8; 32];
+        let a = SecureChannel {
+            send_key: ChaCha20Poly1305::new((&key_a).into()),
+            recv_key: ChaCha20Poly1305::new((&key_b).into()),
+            send_counter: 0,
+            recv_counter: 0,
+            peer_fingerprint: "peer-a".into(),
+        };
+        let b = SecureChannel {
+            send_key: ChaCha20Poly1305::new((&key_b).into()),
+            recv_key: ChaCha20Poly1305::new((&key_a).into()),
+            send_counter: 0,
+            recv_counter: 0,
+            peer_fingerprint: "peer-b".into(),
+        };
+        (a, b)
+    }
+
+    #[test]
+    fn nonce_from_counter_is_big_endian_and_distinct() {
+        assert_eq!(nonce_from_counter(0), [0u8; 12]);
+        let one = nonce_from_counter(1);
+        assert_eq!(&one[..4], &[0u8; 4]);
+        assert_eq!(&one[4..], &1u64.to_be_bytes());
+        assert_ne!(nonce_from_counter(1), nonce_from_counter(2));
+    }
+
+    #[test]
+    fn encrypt_decrypt_roundtrip_across_directions() {
+        let (mut a, mut b) = channel_pair();
+        let ct = a.encrypt(b"hello controll").unwrap();
+        let pt = b.decrypt(&ct).unwrap();
+        assert_eq!(pt, b"hello controll");
+    }
+
+    #[test]
+    fn decrypt_rejects_tampered_ciphertext() {
+        let (mut a, mut b) = channel_pair();
+        let mut ct = a.encrypt(b"hello controll").unwrap();
+        *ct.last_mut().unwrap() ^= 0xff;
+        assert!(b.decrypt(&ct).is_err());
+    }
+
+    #[test]
+    fn encrypt_fails_when_nonce_counter_exhausted() {
+        let (mut a, _b) = channel_pair();
+        a.send_counter = u64::MAX;
+        assert!(a.encrypt(b"x").is_err());
+    }
+
+    #[test]
+    fn decrypt_fails_when_nonce_counter_exhausted() {
+        let (_a, mut b) = channel_pair();
+        b.recv_counter = u64::MAX;
+        assert!(b.decrypt(&[0u8; 16]).is_err());
+    }
+}
@@ -1,7 +1,9 @@
 use crate::protocol::Message;
+use once_cell::sync::Lazy;
 use std::collections::HashMap;
 use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
 const MCAST_GRP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
@@ -11,9 +13,16 @@ const DEVICE_TTL: Duration = Duration::from_secs(8);
 
 #[derive(Clone, Debug)]
 pub struct DeviceInfo {
+    /// The device's ed25519 fingerprint, used as its stable identity both in
+    /// `BEACON.instance_id` and as the trust-store key.
+    pub instance_id: String,
     pub name: String,
     pub ip: String,
+    /// Address to actually dial: the sender's UPnP/STUN-reachable address
+    /// when set, otherwise the same as `ip`.
+    pub reachable_host: String,
     pub ws_port: u16,
+    pub transports: Vec<String>,
     pub last_seen: Instant,
 }
 
@@ -21,7 +30,14 @@ pub struct DeviceInfo {
 pub enum DiscEvent {
     DevicesChanged(Vec<DeviceInfo>),
     RequestReceived { from_inst: String, from_name: String, ws_host: String, ws_port: u16 },
-    ResponseAccepted { host: String, port: u16 },
+    ResponseAccepted { fingerprint: String, host: String, port: u16 },
+}
+
+/// Commands sent into a running discovery loop from another thread (e.g. the
+/// GUI adding a manual peer outside the loop's own ownership of `Discovery`).
+#[derive(Clone, Debug)]
+pub enum DiscCommand {
+    AddPeer { host: String, ws_port: u16 },
 }
 
 pub struct Discovery {
@@ -29,11 +45,35 @@ pub struct Discovery {
     pub name: String,
     pub ws_port: u16,
     pub devices: HashMap<String, DeviceInfo>,
+    /// Devices reachable only by direct (unicast) addressing, e.g. across a
+    /// VPN or different subnet where multicast doesn't reach. Beaconed to
+    /// individually once per `BEACON_INTERVAL`, alongside the LAN multicast beacon.
+    pub manual_peers: Vec<(String, u16)>,
+    /// Public IP address learned via UPnP-IGD or STUN, if WAN reachability
+    /// was set up; advertised in `BEACON.reachable_host` instead of the LAN ip.
+    external_host: Option<String>,
     sock: UdpSocket,
     event_tx: Option<Sender<DiscEvent>>,
 }
 
-fn primary_ip() -> String {
+/// Human-readable names learned for fingerprints via `BEACON`/`REQUEST_CONTROL`,
+/// so `trust::request_decision` can show something better than the
+/// fingerprint itself when prompting for a first-time connection. Global
+/// (not on `Discovery`) because the WS/QUIC accept loops that need it run
+/// independently of whichever thread owns the `Discovery` instance.
+static KNOWN_NAMES: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn note_name(fingerprint: &str, name: &str) {
+    KNOWN_NAMES.lock().unwrap().insert(fingerprint.to_string(), name.to_string());
+}
+
+/// Best-known name for `fingerprint`, if we've ever seen a `BEACON` or
+/// `REQUEST_CONTROL` from it.
+pub fn name_for(fingerprint: &str) -> Option<String> {
+    KNOWN_NAMES.lock().unwrap().get(fingerprint).cloned()
+}
+
+pub fn primary_ip() -> String {
     let s = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok();
     if let Some(s) = s {
         let _ = s.connect((Ipv4Addr::new(8, 8, 8, 8), 80));
@@ -46,10 +86,16 @@ fn primary_ip() -> String {
 
 impl Discovery {
     pub fn new(instance_id: String, name: String, ws_port: u16) -> std::io::Result<Self> {
-        Self::new_with_sender(instance_id, name, ws_port, None)
+        Self::new_with_sender(instance_id, name, ws_port, None, None)
     }
 
-    pub fn new_with_sender(instance_id: String, name: String, ws_port: u16, event_tx: Option<Sender<DiscEvent>>) -> std::io::Result<Self> {
+    pub fn new_with_sender(
+        instance_id: String,
+        name: String,
+        ws_port: u16,
+        event_tx: Option<Sender<DiscEvent>>,
+        external_host: Option<String>,
+    ) -> std::io::Result<Self> {
         use socket2::{Domain, Protocol, Socket, Type};
         // Determine primary interface IP for joining/sending
         let local_ip: Ipv4Addr = primary_ip().parse().unwrap_or(Ipv4Addr::UNSPECIFIED);
@@ -69,7 +115,25 @@ impl Discovery {
         s.set_multicast_if_v4(&local_ip)?;
         let sock: UdpSocket = s.into();
         sock.set_read_timeout(Some(Duration::from_millis(500)))?;
-        Ok(Self { instance_id, name, ws_port, devices: HashMap::new(), sock, event_tx })
+        Ok(Self {
+            instance_id,
+            name,
+            ws_port,
+            devices: HashMap::new(),
+            manual_peers: Vec::new(),
+            external_host,
+            sock,
+            event_tx,
+        })
+    }
+
+    /// Register a device we can't reach via LAN multicast (different
+    /// subnet/VPN): we'll beacon it unicast and can `send_request` to it
+    /// directly with its IP regardless of whether it ever shows up here.
+    pub fn add_peer(&mut self, host: String, ws_port: u16) {
+        if !self.manual_peers.iter().any(|(h, p)| *h == host && *p == ws_port) {
+            self.manual_peers.push((host, ws_port));
+        }
     }
 
     fn send_unicast(&self, target_ip: &str, msg: &Message) {
@@ -100,8 +164,21 @@ impl Discovery {
     pub fn tick(&mut self, last_beacon: &mut Instant) {
         let now = Instant::now();
         if now.duration_since(*last_beacon) >= BEACON_INTERVAL {
-            let msg = Message::BEACON { instance_id: self.instance_id.clone(), name: self.name.clone(), ip: primary_ip(), ws_port: self.ws_port, version: 1 };
+            let ip = primary_ip();
+            let reachable_host = self.external_host.clone().unwrap_or_else(|| ip.clone());
+            let msg = Message::BEACON {
+                instance_id: self.instance_id.clone(),
+                name: self.name.clone(),
+                ip,
+                ws_port: self.ws_port,
+                version: 1,
+                transports: crate::transport::supported_transports(),
+                reachable_host,
+            };
             self.send_broadcast(&msg);
+            for (host, _) in self.manual_peers.clone() {
+                self.send_unicast(&host, &msg);
+            }
             *last_beacon = now;
             println!("[disc] beacon sent {}:{}", primary_ip(), self.ws_port);
         }
@@ -112,9 +189,11 @@ impl Discovery {
             if let Ok(text) = std::str::from_utf8(&buf[..n]) {
                 if let Ok(msg) = serde_json::from_str::<Message>(text) {
                     match msg {
-                        Message::BEACON { instance_id, name, ip, ws_port, .. } => {
+                        Message::BEACON { instance_id, name, ip, ws_port, transports, reachable_host, .. } => {
                             if instance_id != self.instance_id {
-                                self.devices.insert(instance_id.clone(), DeviceInfo { name, ip: ip.clone(), ws_port, last_seen: Instant::now() });
+                                note_name(&instance_id, &name);
+                                let reachable_host = if reachable_host.is_empty() { ip.clone() } else { reachable_host };
+                                self.devices.insert(instance_id.clone(), DeviceInfo { instance_id: instance_id.clone(), name, ip: ip.clone(), reachable_host, ws_port, transports, last_seen: Instant::now() });
                                 println!("[disc] seen {} @ {}:{}", instance_id, ip, ws_port);
                                 if let Some(tx) = &self.event_tx {
                                     let list: Vec<DeviceInfo> = self.devices.values().cloned().collect();
@@ -122,20 +201,23 @@ impl Discovery {
                                 }
                             }
                         }
-                        Message::RequestControl { from, to, name, ws_host, ws_port, options: _ } => {
+                        Message::REQUEST_CONTROL { from, to, name, ws_host, ws_port, options: _ } => {
                             if to.as_deref().map(|t| t == self.instance_id).unwrap_or(true) {
+                                note_name(&from, &name);
                                 println!("[disc] request from {} ({})", name, from);
                                 if let Some(tx) = &self.event_tx {
                                     let _ = tx.send(DiscEvent::RequestReceived { from_inst: from, from_name: name, ws_host, ws_port });
                                 }
                             }
                         }
-                        Message::ResponseControl { from, accepted } => {
+                        Message::RESPONSE_CONTROL { from, accepted } => {
                             println!("[disc] response from {} accepted={}", from, accepted);
                             if accepted {
                                 let host = match src { std::net::SocketAddr::V4(v4) => v4.ip().to_string(), _ => "127.0.0.1".to_string() };
                                 let port = self.devices.get(&from).map(|d| d.ws_port).unwrap_or(self.ws_port);
-                                if let Some(tx) = &self.event_tx { let _ = tx.send(DiscEvent::ResponseAccepted { host, port }); }
+                                if let Some(tx) = &self.event_tx {
+                                    let _ = tx.send(DiscEvent::ResponseAccepted { fingerprint: from, host, port });
+                                }
                             }
                         }
                     }
@@ -145,20 +227,50 @@ impl Discovery {
     }
 
     pub fn send_request(&self, target_ip: &str, options: serde_json::Value, to: Option<String>) {
-        let msg = Message::RequestControl { from: self.instance_id.clone(), to, name: self.name.clone(), ws_host: primary_ip(), ws_port: self.ws_port, options };
+        // Key by the target's fingerprint when we know it, else fall back to
+        // the IP we're dialing; either way it matches what `ResponseAccepted`
+        // carries back for this same target.
+        let key = to.as_deref().unwrap_or(target_ip);
+        crate::transport::set_last_requested(key, &options);
+        let msg = Message::REQUEST_CONTROL { from: self.instance_id.clone(), to, name: self.name.clone(), ws_host: primary_ip(), ws_port: self.ws_port, options };
         self.send_unicast(target_ip, &msg);
         println!("[disc] request sent to {}", target_ip);
     }
 
     pub fn send_response(&self, target_ip: &str, accepted: bool) {
-        let msg = Message::ResponseControl { from: self.instance_id.clone(), accepted };
+        let msg = Message::RESPONSE_CONTROL { from: self.instance_id.clone(), accepted };
         self.send_unicast(target_ip, &msg);
         println!("[disc] response sent to {} accepted={}", target_ip, accepted);
     }
 }
 
-pub fn run_loop_with_sender(inst: String, name: String, ws_port: u16, event_tx: Option<Sender<DiscEvent>>) -> std::io::Result<()> {
-    let mut disc = Discovery::new_with_sender(inst, name, ws_port, event_tx)?;
+pub fn run_loop_with_sender(
+    inst: String,
+    name: String,
+    ws_port: u16,
+    event_tx: Option<Sender<DiscEvent>>,
+) -> std::io::Result<()> {
+    run_loop(inst, name, ws_port, event_tx, None, None)
+}
+
+pub fn run_loop(
+    inst: String,
+    name: String,
+    ws_port: u16,
+    event_tx: Option<Sender<DiscEvent>>,
+    external_host: Option<String>,
+    cmd_rx: Option<Receiver<DiscCommand>>,
+) -> std::io::Result<()> {
+    let mut disc = Discovery::new_with_sender(inst, name, ws_port, event_tx, external_host)?;
     let mut last_beacon = Instant::now() - BEACON_INTERVAL;
-    loop { disc.tick(&mut last_beacon); }
+    loop {
+        if let Some(rx) = &cmd_rx {
+            while let Ok(cmd) = rx.try_recv() {
+                match cmd {
+                    DiscCommand::AddPeer { host, ws_port } => disc.add_peer(host, ws_port),
+                }
+            }
+        }
+        disc.tick(&mut last_beacon);
+    }
 }
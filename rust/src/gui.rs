@@ -1,46 +1,126 @@
-use crate::discovery::{DiscEvent, DeviceInfo};
+use crate::discovery::{DiscCommand, DiscEvent, DeviceInfo};
+use crate::identity::Identity;
 use crate::protocol::Message;
+use crate::trust;
 use eframe::egui;
+use std::collections::HashMap;
 use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
 
 pub struct UiApp {
     rx: Receiver<DiscEvent>,
+    trust_rx: Receiver<(String, String)>,
+    cmd_tx: Sender<DiscCommand>,
+    identity: Arc<Identity>,
+    rt_handle: tokio::runtime::Handle,
+    /// Latest discovery snapshot, keyed by fingerprint, so an accepted
+    /// session's reconnect loop can look up a fresher address if the peer's
+    /// IP changes.
+    devices_shared: Arc<Mutex<HashMap<String, DeviceInfo>>>,
     devices: Vec<DeviceInfo>,
     selected: Option<usize>,
     incoming: Option<(String, String, u16)>, // (from_name, ws_host, ws_port)
+    pending_trust: Vec<(String, String)>, // (fingerprint, name)
+    trusted_rename: HashMap<String, String>, // fingerprint -> in-progress rename text
     status: String,
     ws_port: u16,
     inst: String,
     name: String,
+    use_quic: bool,
+    use_absolute: bool,
+    peer_addr: String,
 }
 
 impl UiApp {
-    pub fn new(rx: Receiver<DiscEvent>, ws_port: u16, inst: String, name: String) -> Self {
-        Self { rx, devices: vec![], selected: None, incoming: None, status: String::new(), ws_port, inst, name }
+    pub fn new(
+        rx: Receiver<DiscEvent>,
+        trust_rx: Receiver<(String, String)>,
+        cmd_tx: Sender<DiscCommand>,
+        identity: Arc<Identity>,
+        rt_handle: tokio::runtime::Handle,
+        ws_port: u16,
+        inst: String,
+        name: String,
+    ) -> Self {
+        Self {
+            rx, trust_rx, cmd_tx, identity, rt_handle, devices_shared: Arc::new(Mutex::new(HashMap::new())),
+            devices: vec![], selected: None, incoming: None, pending_trust: vec![], trusted_rename: HashMap::new(),
+            status: String::new(), ws_port, inst, name, use_quic: false, use_absolute: false, peer_addr: String::new(),
+        }
+    }
+
+    /// Add a device outside multicast reach (different subnet/VPN). It's
+    /// registered for periodic beaconing so it can discover us back, and we
+    /// also fire REQUEST_CONTROL at it directly right away: on a one-way/NAT
+    /// link the peer may never beacon to us first, so waiting for it to show
+    /// up in `self.devices` before "Request Control" is clickable would mean
+    /// pasting an endpoint never actually requests control of it.
+    fn add_manual_peer(&mut self) {
+        if let Some((host, port)) = self.peer_addr.rsplit_once(':').and_then(|(h, p)| Some((h.to_string(), p.parse::<u16>().ok()?))) {
+            let _ = self.cmd_tx.send(DiscCommand::AddPeer { host: host.clone(), ws_port: port });
+            self.send_request_unicast(&host, None);
+            self.status = format!("Requested control from {}", self.peer_addr);
+            self.peer_addr.clear();
+        } else {
+            self.status = "Peer must be host:port".into();
+        }
     }
 
     fn poll_events(&mut self) {
         while let Ok(ev) = self.rx.try_recv() {
             match ev {
-                DiscEvent::DevicesChanged(list) => { self.devices = list; }
+                DiscEvent::DevicesChanged(list) => {
+                    *self.devices_shared.lock().unwrap() = list.iter().map(|d| (d.instance_id.clone(), d.clone())).collect();
+                    self.devices = list;
+                }
                 DiscEvent::RequestReceived { from_inst: _, from_name, ws_host, ws_port } => {
                     self.incoming = Some((from_name, ws_host, ws_port));
                 }
-                DiscEvent::ResponseAccepted { host: _, port: _ } => {
-                    self.status = "Response accepted".into();
+                DiscEvent::ResponseAccepted { fingerprint, host, port } => {
+                    self.status = format!("Response accepted from {fingerprint}, maintaining session");
+                    self.rt_handle.spawn(crate::maintain_session(
+                        fingerprint,
+                        host,
+                        port,
+                        self.identity.clone(),
+                        self.devices_shared.clone(),
+                    ));
                 }
             }
         }
+        while let Ok(prompt) = self.trust_rx.try_recv() {
+            if !self.pending_trust.iter().any(|(fp, _)| *fp == prompt.0) {
+                self.pending_trust.push(prompt);
+            }
+        }
     }
 
-    fn send_request_unicast(&self, ip: &str) {
-        let msg = Message::RequestControl { from: self.inst.clone(), to: None, name: self.name.clone(), ws_host: primary_ip(), ws_port: self.ws_port, options: serde_json::json!({"map":"relative"}) };
+    /// `target_fingerprint` is `None` for a manual peer we haven't heard a
+    /// beacon from yet; the request still goes out, just without a `to` to
+    /// narrow it to one instance at that address. Keys the pending-transport
+    /// lookup by fingerprint when known, else by `ip`, matching
+    /// `Discovery::send_request`.
+    fn send_request_unicast(&self, ip: &str, target_fingerprint: Option<&str>) {
+        let options = serde_json::json!({
+            "map": if self.use_absolute { "absolute" } else { "relative" },
+            "transport": if self.use_quic { "quic" } else { "ws" },
+        });
+        let key = target_fingerprint.unwrap_or(ip);
+        crate::transport::set_last_requested(key, &options);
+        let msg = Message::REQUEST_CONTROL {
+            from: self.inst.clone(),
+            to: target_fingerprint.map(String::from),
+            name: self.name.clone(),
+            ws_host: primary_ip(),
+            ws_port: self.ws_port,
+            options,
+        };
         send_udp_json(ip, &msg);
     }
 
     fn send_response_unicast(&self, ip: &str, accepted: bool) {
-        let msg = Message::ResponseControl { from: self.inst.clone(), accepted };
+        let msg = Message::RESPONSE_CONTROL { from: self.inst.clone(), accepted };
         send_udp_json(ip, &msg);
     }
 }
@@ -77,9 +157,17 @@ impl eframe::App for UiApp {
                     }
                 });
                 ui.vertical(|ui| {
+                    ui.checkbox(&mut self.use_quic, "Use QUIC transport");
+                    ui.checkbox(&mut self.use_absolute, "Absolute mouse mapping");
                     if ui.button("Request Control").clicked() {
-                        if let Some(i) = self.selected { if let Some(d) = self.devices.get(i) { self.send_request_unicast(&d.ip); self.status = format!("Requested {}", d.name); } }
+                        if let Some(i) = self.selected { if let Some(d) = self.devices.get(i) { self.send_request_unicast(&d.reachable_host, Some(&d.instance_id)); self.status = format!("Requested {}", d.name); } }
                     }
+                    ui.separator();
+                    ui.label("Add peer outside LAN (host:port):");
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut self.peer_addr);
+                        if ui.button("Add Peer").clicked() { self.add_manual_peer(); }
+                    });
                     let mut action: Option<(String, bool)> = None;
                     if let Some((from_name, ws_host, _ws_port)) = self.incoming.clone() {
                         ui.separator();
@@ -90,6 +178,44 @@ impl eframe::App for UiApp {
                     if let Some((host, accepted)) = action { self.send_response_unicast(&host, accepted); self.incoming = None; }
                 });
             });
+            if !self.pending_trust.is_empty() {
+                ui.separator();
+                ui.label("New devices awaiting trust:");
+                let mut decided: Option<(String, String, bool)> = None;
+                for (fingerprint, name) in &self.pending_trust {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{} ({})", name, fingerprint));
+                        if ui.button("Accept").clicked() { decided = Some((fingerprint.clone(), name.clone(), true)); }
+                        if ui.button("Reject").clicked() { decided = Some((fingerprint.clone(), name.clone(), false)); }
+                    });
+                }
+                if let Some((fingerprint, name, accepted)) = decided {
+                    trust::decide(&fingerprint, &name, accepted);
+                    self.pending_trust.retain(|(fp, _)| *fp != fingerprint);
+                }
+            }
+            ui.separator();
+            ui.label("Trusted devices:");
+            let mut revoke: Option<String> = None;
+            let mut rename: Option<(String, String)> = None;
+            for d in trust::list() {
+                ui.horizontal(|ui| {
+                    let text = self.trusted_rename.entry(d.fingerprint.clone()).or_insert_with(|| d.name.clone());
+                    ui.text_edit_singleline(text);
+                    if ui.button("Rename").clicked() { rename = Some((d.fingerprint.clone(), text.clone())); }
+                    let mut auto_accept = d.auto_accept;
+                    if ui.checkbox(&mut auto_accept, "Auto-accept").changed() {
+                        trust::set_auto_accept(&d.fingerprint, auto_accept);
+                    }
+                    ui.label(d.endpoint.as_deref().unwrap_or("(never connected)"));
+                    if ui.button("Revoke").clicked() { revoke = Some(d.fingerprint.clone()); }
+                });
+            }
+            if let Some((fingerprint, name)) = rename { trust::rename(&fingerprint, &name); }
+            if let Some(fingerprint) = revoke {
+                trust::revoke(&fingerprint);
+                self.trusted_rename.remove(&fingerprint);
+            }
         });
         ctx.request_repaint_after(std::time::Duration::from_millis(100));
     }
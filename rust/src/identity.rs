@@ -0,0 +1,114 @@
+//! Per-instance ed25519 identity used to authenticate control sessions.
+//!
+//! Each installation generates a static keypair on first run and persists it
+//! under `CONTROLL_HOME` (falling back to `~/.controll`). The instance's
+//! `instance_id` used throughout discovery/protocol is the hex fingerprint of
+//! the public key, so `BEACON`s are self-certifying: the id can't be spoofed
+//! without the matching private key.
+
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+
+pub struct Identity {
+    pub signing_key: SigningKey,
+}
+
+impl Identity {
+    /// Load the persisted identity, or generate and persist a new one.
+    pub fn load_or_create() -> std::io::Result<Self> {
+        let path = identity_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if let Ok(bytes) = fs::read(&path) {
+            if let Ok(arr) = <[u8; 32]>::try_from(bytes.as_slice()) {
+                return Ok(Self { signing_key: SigningKey::from_bytes(&arr) });
+            }
+        }
+        let signing_key = SigningKey::generate(&mut OsRng);
+        write_private(&path, &signing_key.to_bytes())?;
+        Ok(Self { signing_key })
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.signing_key.verifying_key()
+    }
+
+    /// Fingerprint used as `instance_id` everywhere (hex, 32 chars).
+    pub fn fingerprint(&self) -> String {
+        fingerprint_of(&self.verifying_key())
+    }
+}
+
+/// Fingerprint of an arbitrary ed25519 public key (sha256, truncated to 16 bytes).
+pub fn fingerprint_of(vk: &VerifyingKey) -> String {
+    let digest = Sha256::digest(vk.as_bytes());
+    hex::encode(&digest[..16])
+}
+
+fn data_dir() -> PathBuf {
+    if let Ok(p) = std::env::var("CONTROLL_HOME") {
+        return PathBuf::from(p);
+    }
+    if let Ok(home) = std::env::var("HOME") {
+        return PathBuf::from(home).join(".controll");
+    }
+    PathBuf::from(".controll")
+}
+
+fn identity_path() -> PathBuf {
+    data_dir().join("identity.ed25519")
+}
+
+/// Writes `contents` to `path` with owner-only (0600) permissions, set at
+/// creation time rather than after the fact, so the raw ed25519 private key
+/// is never briefly readable under the process's default umask (e.g. 0644
+/// on a shared machine would let any local user read it and impersonate
+/// this instance).
+#[cfg(unix)]
+fn write_private(path: &PathBuf, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &PathBuf, contents: &[u8]) -> std::io::Result<()> {
+    fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_of_is_deterministic() {
+        let key = SigningKey::generate(&mut OsRng);
+        let vk = key.verifying_key();
+        assert_eq!(fingerprint_of(&vk), fingerprint_of(&vk));
+    }
+
+    #[test]
+    fn fingerprint_of_differs_across_keys() {
+        let a = SigningKey::generate(&mut OsRng).verifying_key();
+        let b = SigningKey::generate(&mut OsRng).verifying_key();
+        assert_ne!(fingerprint_of(&a), fingerprint_of(&b));
+    }
+
+    #[test]
+    fn fingerprint_of_is_32_hex_chars() {
+        let vk = SigningKey::generate(&mut OsRng).verifying_key();
+        let fp = fingerprint_of(&vk);
+        assert_eq!(fp.len(), 32);
+        assert!(fp.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+}
@@ -1,53 +1,299 @@
-use futures::SinkExt;
-use rdev::{Event, EventType, Key};
+use rdev::{Button as RdevButton, Event, EventType, Key};
+use std::collections::HashSet;
 
+use crate::protocol::{InputEvent, MouseButton};
 use crate::state::set_capture;
+use crate::transport::Transport;
 
-pub fn run_capture_client(url: String) {
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<String>();
-    std::thread::spawn(move || {
-        if let Ok(rt) = tokio::runtime::Runtime::new() {
-            rt.block_on(async move {
-                if let Ok((mut ws, _)) = tokio_tungstenite::connect_async(&url).await {
-                    while let Some(msg) = rx.recv().await {
-                        let _ = ws.send(tokio_tungstenite::tungstenite::protocol::Message::Text(msg)).await;
-                    }
+fn to_enigo_button(b: MouseButton) -> enigo::Button {
+    match b {
+        MouseButton::Left => enigo::Button::Left,
+        MouseButton::Right => enigo::Button::Right,
+        MouseButton::Middle => enigo::Button::Middle,
+    }
+}
+
+/// Applies received `InputEvent`s to the local machine (the controlled
+/// side), shared by both the WS and QUIC transports. Tracks which keys and
+/// buttons are currently held down so a dropped session can release them
+/// rather than leaving the machine with stuck input.
+#[derive(Default)]
+pub struct InputState {
+    enigo: Option<enigo::Enigo>,
+    down_keys: HashSet<String>,
+    down_buttons: HashSet<MouseButton>,
+}
+
+impl InputState {
+    fn enigo(&mut self) -> Option<&mut enigo::Enigo> {
+        if self.enigo.is_none() {
+            self.enigo = enigo::Enigo::new(&enigo::Settings::default()).ok();
+        }
+        self.enigo.as_mut()
+    }
+
+    pub fn apply(&mut self, ev: InputEvent) {
+        use enigo::{Axis, Coordinate, Direction, Keyboard, Mouse};
+        match ev {
+            InputEvent::MouseMove { x, y } => {
+                if let Some(e) = self.enigo() { let _ = e.move_mouse(x, y, Coordinate::Abs); }
+            }
+            InputEvent::MouseMoveRel { dx, dy } => {
+                if let Some(e) = self.enigo() { let _ = e.move_mouse(dx, dy, Coordinate::Rel); }
+            }
+            InputEvent::MouseDown { button } => {
+                self.down_buttons.insert(button);
+                if let Some(e) = self.enigo() { let _ = e.button(to_enigo_button(button), Direction::Press); }
+            }
+            InputEvent::MouseUp { button } => {
+                self.down_buttons.remove(&button);
+                if let Some(e) = self.enigo() { let _ = e.button(to_enigo_button(button), Direction::Release); }
+            }
+            InputEvent::Scroll { dx, dy } => {
+                if let Some(e) = self.enigo() {
+                    if dy != 0 { let _ = e.scroll(dy, Axis::Vertical); }
+                    if dx != 0 { let _ = e.scroll(dx, Axis::Horizontal); }
+                }
+            }
+            InputEvent::KeyDown { key } => {
+                self.down_keys.insert(key.clone());
+                if let Some(k) = lookup_enigo_key(&key) {
+                    if let Some(e) = self.enigo() { let _ = e.key(k, Direction::Press); }
+                }
+            }
+            InputEvent::KeyUp { key } => {
+                self.down_keys.remove(&key);
+                if let Some(k) = lookup_enigo_key(&key) {
+                    if let Some(e) = self.enigo() { let _ = e.key(k, Direction::Release); }
                 }
-            });
+            }
         }
-    });
+    }
+
+    /// Release everything still marked down. Call this when a session ends
+    /// so a dropped connection never leaves modifiers/buttons stuck.
+    pub fn reset(&mut self) {
+        use enigo::{Direction, Keyboard, Mouse};
+        let keys: Vec<String> = self.down_keys.drain().collect();
+        let buttons: Vec<MouseButton> = self.down_buttons.drain().collect();
+        for key in keys {
+            if let Some(k) = lookup_enigo_key(&key) {
+                if let Some(e) = self.enigo() { let _ = e.key(k, Direction::Release); }
+            }
+        }
+        for button in buttons {
+            if let Some(e) = self.enigo() { let _ = e.button(to_enigo_button(button), Direction::Release); }
+        }
+    }
+}
+
+/// Map an `rdev` key to the stable string name carried over the wire. Using
+/// rdev's own `Debug` form keeps this in sync with whatever keys rdev adds,
+/// at the cost of being rdev-specific on the wire (fine, since both ends of
+/// a controll session speak rdev/enigo already).
+fn key_name(k: Key) -> String {
+    format!("{:?}", k)
+}
+
+fn button_name(b: RdevButton) -> Option<MouseButton> {
+    match b {
+        RdevButton::Left => Some(MouseButton::Left),
+        RdevButton::Right => Some(MouseButton::Right),
+        RdevButton::Middle => Some(MouseButton::Middle),
+        RdevButton::Unknown(_) => None,
+    }
+}
+
+/// Map the wire key name (rdev's `Debug` form, see [`key_name`]) back to an
+/// `enigo::Key` on the controlled side. Single characters and digits fall
+/// through to `Key::Unicode`; anything we don't recognize is dropped rather
+/// than guessed at.
+pub fn lookup_enigo_key(name: &str) -> Option<enigo::Key> {
+    use enigo::Key as EKey;
+    if let Some(rest) = name.strip_prefix("Key") {
+        if rest.len() == 1 {
+            return Some(EKey::Unicode(rest.chars().next()?.to_ascii_lowercase()));
+        }
+    }
+    if let Some(rest) = name.strip_prefix("Num") {
+        if let Ok(d) = rest.parse::<u8>() {
+            return Some(EKey::Unicode((b'0' + d) as char));
+        }
+    }
+    Some(match name {
+        "Return" | "KpReturn" => EKey::Return,
+        "Escape" => EKey::Escape,
+        "Backspace" => EKey::Backspace,
+        "Tab" => EKey::Tab,
+        "Space" => EKey::Space,
+        "CapsLock" => EKey::CapsLock,
+        "ShiftLeft" | "ShiftRight" => EKey::Shift,
+        "ControlLeft" | "ControlRight" => EKey::Control,
+        "Alt" | "AltGr" => EKey::Alt,
+        "MetaLeft" | "MetaRight" => EKey::Meta,
+        "UpArrow" => EKey::UpArrow,
+        "DownArrow" => EKey::DownArrow,
+        "LeftArrow" => EKey::LeftArrow,
+        "RightArrow" => EKey::RightArrow,
+        "Home" => EKey::Home,
+        "End" => EKey::End,
+        "PageUp" => EKey::PageUp,
+        "PageDown" => EKey::PageDown,
+        "Delete" => EKey::Delete,
+        "Insert" => EKey::Insert,
+        "F1" => EKey::F1,
+        "F2" => EKey::F2,
+        "F3" => EKey::F3,
+        "F4" => EKey::F4,
+        "F5" => EKey::F5,
+        "F6" => EKey::F6,
+        "F7" => EKey::F7,
+        "F8" => EKey::F8,
+        "F9" => EKey::F9,
+        "F10" => EKey::F10,
+        "F11" => EKey::F11,
+        "F12" => EKey::F12,
+        _ => return None,
+    })
+}
+
+/// Captures local keyboard/mouse input and forwards it to `transport` (an
+/// already-handshaken session, built by `ws::run_ws_client` or
+/// `quic::run_quic_client`) until the grab loop exits or the peer drops the
+/// connection. `rdev::grab` blocks its thread for as long as it runs, so
+/// captured events are handed off over a channel to this async task, which
+/// owns the transport and actually sends them.
+pub async fn run_capture_client(mut transport: Box<dyn Transport>, relative: bool) {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<InputEvent>();
 
     let capturing = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
     let capturing_cb = capturing.clone();
+    let last_pos = std::sync::Arc::new(std::sync::Mutex::new(None::<(f64, f64)>));
+
+    let send = move |ev: InputEvent| {
+        let _ = tx.send(ev);
+    };
 
     // Global grab: suppress local when capturing (toggle on F12)
-    let _ = rdev::grab(move |event: Event| {
-        match event.event_type {
-            EventType::KeyPress(k) => {
-                // Toggle on F13 (macOS keycode 105). Fallback: also accept F12.
-                let mut is_f13 = false;
-                #[allow(unused_mut)]
-                let mut code_opt: Option<u32> = None;
-                if let Key::Unknown(c) = k { code_opt = Some(c); }
-                #[cfg(target_os = "macos")]
-                { if let Some(c) = code_opt { if c == 105 { is_f13 = true; } } }
-                if is_f13 || matches!(k, Key::F12) {
-                    let now = !capturing_cb.load(std::sync::atomic::Ordering::Relaxed);
-                    capturing_cb.store(now, std::sync::atomic::Ordering::Relaxed);
-                    return Some(event);
-                }
-            }
-            EventType::MouseMove { x, y } => {
-                if capturing_cb.load(std::sync::atomic::Ordering::Relaxed) {
-                    let payload = serde_json::json!({ "type": "mouse_move", "x": x as i32, "y": y as i32 }).to_string();
-                    let _ = tx.send(payload);
-                    return None; // suppress locally
-                }
-            }
-            _ => {
-                if capturing_cb.load(std::sync::atomic::Ordering::Relaxed) { return None; }
+    std::thread::spawn(move || {
+        let _ = rdev::grab(move |event: Event| {
+            match event.event_type {
+                EventType::KeyPress(k) => {
+                    // Toggle on F13 (macOS keycode 105). Fallback: also accept F12.
+                    let mut is_f13 = false;
+                    #[allow(unused_mut)]
+                    let mut code_opt: Option<u32> = None;
+                    if let Key::Unknown(c) = k { code_opt = Some(c); }
+                    #[cfg(target_os = "macos")]
+                    { if let Some(c) = code_opt { if c == 105 { is_f13 = true; } } }
+                    if is_f13 || matches!(k, Key::F12) {
+                        let now = !capturing_cb.load(std::sync::atomic::Ordering::Relaxed);
+                        capturing_cb.store(now, std::sync::atomic::Ordering::Relaxed);
+                        set_capture(now);
+                        return Some(event);
+                    }
+                    if capturing_cb.load(std::sync::atomic::Ordering::Relaxed) {
+                        send(InputEvent::KeyDown { key: key_name(k) });
+                        return None;
+                    }
+                }
+                EventType::KeyRelease(k) => {
+                    if capturing_cb.load(std::sync::atomic::Ordering::Relaxed) {
+                        send(InputEvent::KeyUp { key: key_name(k) });
+                        return None;
+                    }
+                }
+                EventType::ButtonPress(b) => {
+                    if capturing_cb.load(std::sync::atomic::Ordering::Relaxed) {
+                        if let Some(button) = button_name(b) {
+                            send(InputEvent::MouseDown { button });
+                        }
+                        return None;
+                    }
+                }
+                EventType::ButtonRelease(b) => {
+                    if capturing_cb.load(std::sync::atomic::Ordering::Relaxed) {
+                        if let Some(button) = button_name(b) {
+                            send(InputEvent::MouseUp { button });
+                        }
+                        return None;
+                    }
+                }
+                EventType::Wheel { delta_x, delta_y } => {
+                    if capturing_cb.load(std::sync::atomic::Ordering::Relaxed) {
+                        send(InputEvent::Scroll { dx: delta_x as i32, dy: delta_y as i32 });
+                        return None;
+                    }
+                }
+                EventType::MouseMove { x, y } => {
+                    if capturing_cb.load(std::sync::atomic::Ordering::Relaxed) {
+                        let mut pos = last_pos.lock().unwrap();
+                        if relative {
+                            if let Some((px, py)) = *pos {
+                                let dx = (x - px) as i32;
+                                let dy = (y - py) as i32;
+                                if dx != 0 || dy != 0 {
+                                    send(InputEvent::MouseMoveRel { dx, dy });
+                                }
+                            }
+                            *pos = Some((x, y));
+                        } else {
+                            send(InputEvent::MouseMove { x: x as i32, y: y as i32 });
+                        }
+                        return None; // suppress locally
+                    }
+                }
+                _ => {
+                    if capturing_cb.load(std::sync::atomic::Ordering::Relaxed) { return None; }
+                }
             }
-        }
-        Some(event)
+            Some(event)
+        });
     });
+
+    while let Some(ev) = rx.recv().await {
+        if transport.send_event(&ev).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_name_uses_rdev_debug_form() {
+        assert_eq!(key_name(Key::KeyA), "KeyA");
+        assert_eq!(key_name(Key::Num5), "Num5");
+        assert_eq!(key_name(Key::Return), "Return");
+    }
+
+    #[test]
+    fn lookup_enigo_key_maps_single_letters_lowercase() {
+        assert!(matches!(lookup_enigo_key("KeyA"), Some(enigo::Key::Unicode('a'))));
+    }
+
+    #[test]
+    fn lookup_enigo_key_maps_digits() {
+        assert!(matches!(lookup_enigo_key("Num5"), Some(enigo::Key::Unicode('5'))));
+    }
+
+    #[test]
+    fn lookup_enigo_key_maps_named_keys() {
+        assert!(matches!(lookup_enigo_key("Return"), Some(enigo::Key::Return)));
+        assert!(matches!(lookup_enigo_key("F5"), Some(enigo::Key::F5)));
+        assert!(matches!(lookup_enigo_key("ShiftLeft"), Some(enigo::Key::Shift)));
+    }
+
+    #[test]
+    fn lookup_enigo_key_drops_unrecognized_names() {
+        assert!(lookup_enigo_key("NotARealKey").is_none());
+    }
+
+    #[test]
+    fn button_name_drops_unknown_buttons() {
+        assert_eq!(button_name(RdevButton::Left), Some(MouseButton::Left));
+        assert_eq!(button_name(RdevButton::Unknown(7)), None);
+    }
 }
@@ -3,15 +3,85 @@ mod discovery;
 mod ws;
 mod gui;
 mod input;
+mod identity;
+mod crypto;
+mod trust;
+mod transport;
+mod quic;
+mod upnp;
 
-use discovery::{run_loop_with_sender, DiscEvent};
+use discovery::{DiscCommand, DiscEvent};
 use crate::discovery::Discovery;
+use crate::identity::Identity;
 use serde_json::json;
+use std::collections::HashMap;
 use std::env;
-use uuid::Uuid;
+use std::sync::{Arc, Mutex};
 
 fn usage() {
-    eprintln!("Usage: controll-rs <cmd> [args]\n  run [ws_port]\n  list\n  request <ip> [ws_port]\n  ws-server [host] [port]\n  ws-client <ws://host:port>\n  gui [ws_port]\n");
+    eprintln!("Usage: controll-rs <cmd> [args]\n  run [ws_port] [peer_host:peer_port]\n  list\n  request <ip> [ws_port] [ws|quic] [relative|absolute]\n  ws-server [host] [port]\n  ws-client <ws://host:port>\n  gui [ws_port]\n");
+}
+
+/// Try to map the WS/QUIC port externally and learn a reachable address;
+/// `None` if neither UPnP-IGD nor the STUN fallback yields an address.
+async fn discover_external_host(ws_port: u16) -> Option<String> {
+    let lan_ip: std::net::Ipv4Addr = discovery::primary_ip().parse().ok()?;
+    upnp::external_address(lan_ip, ws_port).await.map(|ip| ip.to_string())
+}
+
+fn parse_peer_arg(arg: &str) -> Option<(String, u16)> {
+    let (host, port) = arg.rsplit_once(':')?;
+    Some((host.to_string(), port.parse().ok()?))
+}
+
+const RECONNECT_BASE: std::time::Duration = std::time::Duration::from_secs(1);
+const RECONNECT_MAX: std::time::Duration = std::time::Duration::from_secs(30);
+/// A session that stayed up at least this long is considered healthy, so a
+/// drop after it resets the backoff back to `RECONNECT_BASE` instead of
+/// carrying forward the ramp from whatever caused the *previous* drop.
+const RECONNECT_HEALTHY_AFTER: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Keep an accepted control session alive: run it, and on disconnect redial
+/// the same device with exponential backoff rather than treating the
+/// session as one-shot. The endpoint is refreshed from discovery's device
+/// table each attempt in case the peer's IP changed since we last connected.
+pub(crate) async fn maintain_session(
+    fingerprint: String,
+    mut host: String,
+    mut port: u16,
+    identity: Arc<Identity>,
+    devices: Arc<std::sync::Mutex<HashMap<String, discovery::DeviceInfo>>>,
+) {
+    let mut backoff = RECONNECT_BASE;
+    loop {
+        crate::trust::update_endpoint(&fingerprint, &format!("{host}:{port}"));
+        let attempt_start = std::time::Instant::now();
+        let relative = transport::last_requested_relative(&fingerprint);
+        let result = if transport::last_requested(&fingerprint) == transport::TRANSPORT_QUIC {
+            let addr: Option<std::net::SocketAddr> = format!("{host}:{port}").parse().ok();
+            match addr {
+                Some(addr) => quic::run_quic_client(addr, identity.clone(), relative, Some(fingerprint.clone())).await,
+                None => Err(anyhow::anyhow!("invalid address {host}:{port}")),
+            }
+        } else {
+            let url = format!("ws://{host}:{port}");
+            ws::run_ws_client(&url, identity.clone(), relative, Some(fingerprint.clone())).await
+        };
+        if let Err(e) = result {
+            eprintln!("[reconnect] session with {fingerprint} ended: {e}");
+        }
+        backoff = if attempt_start.elapsed() >= RECONNECT_HEALTHY_AFTER {
+            RECONNECT_BASE
+        } else {
+            (backoff * 2).min(RECONNECT_MAX)
+        };
+        tokio::time::sleep(backoff).await;
+        // if discovery has since seen a fresher address for this device, redial there instead
+        if let Some(d) = devices.lock().unwrap().get(&fingerprint) {
+            host = d.reachable_host.clone();
+            port = d.ws_port;
+        }
+    }
 }
 
 #[tokio::main]
@@ -21,24 +91,45 @@ async fn main() {
     match args[1].as_str() {
         "run" => {
             let ws_port: u16 = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(8765);
-            let inst = Uuid::new_v4().to_string();
+            let identity = Arc::new(Identity::load_or_create().expect("load/create identity"));
+            let inst = identity.fingerprint();
             let name = hostname();
-            // start WS server
-            tokio::spawn(ws::run_ws_server("0.0.0.0", ws_port));
-            // channel for discovery events
+            // No UI to drive an accept/reject prompt in headless `run`, so pass
+            // no trust channel at all: `request_decision`'s `None` branch refuses
+            // unknown fingerprints outright instead of awaiting a reply nobody
+            // will ever send.
+            tokio::spawn(ws::run_ws_server("0.0.0.0", ws_port, identity.clone(), None));
+            tokio::spawn(quic::run_quic_server(([0, 0, 0, 0], ws_port).into(), identity.clone(), None));
+            let external_host = discover_external_host(ws_port).await;
+            // channel for discovery events, and for manual-peer commands (e.g. from a CLI arg here)
             let (tx, rx) = std::sync::mpsc::channel::<DiscEvent>();
+            let (cmd_tx, cmd_rx) = std::sync::mpsc::channel::<DiscCommand>();
+            if let Some(peer) = args.get(3).and_then(|a| parse_peer_arg(a)) {
+                let _ = cmd_tx.send(DiscCommand::AddPeer { host: peer.0, ws_port: peer.1 });
+            }
             // spawn discovery loop in a thread
             let inst2 = inst.clone();
             let name2 = name.clone();
-            std::thread::spawn(move || { let _ = run_loop_with_sender(inst2, name2, ws_port, Some(tx)); });
-            // forward accepted responses into WS client connects
+            std::thread::spawn(move || {
+                let _ = discovery::run_loop(inst2, name2, ws_port, Some(tx), external_host, Some(cmd_rx));
+            });
+            // forward accepted responses into a maintained WS/QUIC session
+            // (reconnects with backoff instead of a one-shot connect),
+            // using whichever transport we asked for in REQUEST_CONTROL
             let handle = tokio::runtime::Handle::current();
+            let identity2 = identity.clone();
+            let devices: Arc<Mutex<HashMap<String, discovery::DeviceInfo>>> = Arc::new(Mutex::new(HashMap::new()));
+            let devices2 = devices.clone();
             std::thread::spawn(move || {
                 while let Ok(ev) = rx.recv() {
                     match ev {
-                        DiscEvent::ResponseAccepted { host, port } => {
-                            let url = format!("ws://{}:{}", host, port);
-                            handle.spawn(async move { let _ = crate::ws::run_ws_client(&url).await; });
+                        DiscEvent::DevicesChanged(list) => {
+                            *devices2.lock().unwrap() = list.into_iter().map(|d| (d.instance_id.clone(), d)).collect();
+                        }
+                        DiscEvent::ResponseAccepted { fingerprint, host, port } => {
+                            let identity3 = identity2.clone();
+                            let devices3 = devices2.clone();
+                            handle.spawn(maintain_session(fingerprint, host, port, identity3, devices3));
                         }
                         _ => {}
                     }
@@ -49,7 +140,8 @@ async fn main() {
         }
         "list" => {
             let ws_port: u16 = 8765;
-            let inst = Uuid::new_v4().to_string();
+            let identity = Identity::load_or_create().expect("load/create identity");
+            let inst = identity.fingerprint();
             let name = hostname();
             let mut disc = Discovery::new(inst, name, ws_port).expect("init discovery");
             let mut last_beacon = std::time::Instant::now() - std::time::Duration::from_secs(2);
@@ -62,34 +154,47 @@ async fn main() {
             if args.len() < 3 { usage(); return; }
             let ip = &args[2];
             let ws_port: u16 = args.get(3).and_then(|s| s.parse().ok()).unwrap_or(8765);
-            let inst = Uuid::new_v4().to_string();
+            let transport = args.get(4).map(String::as_str).unwrap_or(transport::TRANSPORT_WS);
+            let map = args.get(5).map(String::as_str).unwrap_or("relative");
+            let identity = Identity::load_or_create().expect("load/create identity");
+            let inst = identity.fingerprint();
             let name = hostname();
             let disc = Discovery::new(inst, name, ws_port).expect("init discovery");
-            disc.send_request(ip, json!({"map":"relative"}), None);
+            disc.send_request(ip, json!({"map":map,"transport":transport}), None);
             std::thread::sleep(std::time::Duration::from_secs(2));
         }
         "ws-server" => {
             let host = args.get(2).map(String::as_str).unwrap_or("0.0.0.0");
             let port: u16 = args.get(3).and_then(|s| s.parse().ok()).unwrap_or(8765);
-            if let Err(e) = ws::run_ws_server(host, port).await { eprintln!("error: {e}"); }
+            let identity = Arc::new(Identity::load_or_create().expect("load/create identity"));
+            if let Err(e) = ws::run_ws_server(host, port, identity, None).await { eprintln!("error: {e}"); }
         }
         "ws-client" => {
             if args.len() < 3 { usage(); return; }
             let url = &args[2];
-            if let Err(e) = ws::run_ws_client(url).await { eprintln!("error: {e}"); }
+            let identity = Arc::new(Identity::load_or_create().expect("load/create identity"));
+            if let Err(e) = ws::run_ws_client(url, identity, true, None).await { eprintln!("error: {e}"); }
         }
         "gui" => {
             let ws_port: u16 = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(8765);
-            let inst = Uuid::new_v4().to_string();
+            let identity = Arc::new(Identity::load_or_create().expect("load/create identity"));
+            let inst = identity.fingerprint();
             let name = hostname();
-            tokio::spawn(ws::run_ws_server("0.0.0.0", ws_port));
+            let (trust_tx, trust_rx) = std::sync::mpsc::channel();
+            tokio::spawn(ws::run_ws_server("0.0.0.0", ws_port, identity.clone(), Some(trust_tx.clone())));
+            tokio::spawn(quic::run_quic_server(([0, 0, 0, 0], ws_port).into(), identity.clone(), Some(trust_tx)));
+            let external_host = discover_external_host(ws_port).await;
             let (tx, rx) = std::sync::mpsc::channel::<DiscEvent>();
+            let (cmd_tx, cmd_rx) = std::sync::mpsc::channel::<DiscCommand>();
             let inst2 = inst.clone();
             let name2 = name.clone();
-            std::thread::spawn(move || { let _ = discovery::run_loop_with_sender(inst2, name2, ws_port, Some(tx)); });
+            std::thread::spawn(move || {
+                let _ = discovery::run_loop(inst2, name2, ws_port, Some(tx), external_host, Some(cmd_rx));
+            });
+            let rt_handle = tokio::runtime::Handle::current();
             let native_options = eframe::NativeOptions::default();
-            let app = gui::UiApp::new(rx, ws_port, inst, name);
-            let _ = eframe::run_native("Controll", native_options, Box::new(|_| Ok::<Box<dyn eframe::App>, Box<dyn std::error::Error + Send + Sync>>(Box::new(app))));            
+            let app = gui::UiApp::new(rx, trust_rx, cmd_tx, identity, rt_handle, ws_port, inst, name);
+            let _ = eframe::run_native("Controll", native_options, Box::new(|_| Ok::<Box<dyn eframe::App>, Box<dyn std::error::Error + Send + Sync>>(Box::new(app))));
         }
         _ => usage(),
     }
@@ -9,6 +9,14 @@ pub enum Message {
         ip: String,
         ws_port: u16,
         version: u32,
+        /// Transport ids this instance can accept control sessions over,
+        /// e.g. `["ws", "quic"]`. See `transport::supported_transports`.
+        #[serde(default)]
+        transports: Vec<String>,
+        /// Best address for a peer to dial: the UPnP/STUN-discovered public
+        /// address when WAN reachability succeeded, `ip` otherwise.
+        #[serde(default)]
+        reachable_host: String,
     },
     REQUEST_CONTROL {
         from: String,
@@ -23,3 +31,29 @@ pub enum Message {
         accepted: bool,
     },
 }
+
+/// A single captured input event, forwarded from the controlling side to the
+/// controlled side over the session's transport (encrypted WS binary frames,
+/// or QUIC datagrams/stream frames).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputEvent {
+    /// Absolute cursor position, in the controlled machine's screen space.
+    MouseMove { x: i32, y: i32 },
+    /// Cursor delta, used when `options.map == "relative"` so differing
+    /// screen resolutions don't matter.
+    MouseMoveRel { dx: i32, dy: i32 },
+    MouseDown { button: MouseButton },
+    MouseUp { button: MouseButton },
+    Scroll { dx: i32, dy: i32 },
+    KeyDown { key: String },
+    KeyUp { key: String },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
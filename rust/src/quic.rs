@@ -0,0 +1,348 @@
+//! QUIC transport: mouse motion and scroll ride unreliable datagrams (a lost
+//! packet is simply superseded by the next position, instead of piling up
+//! behind a dropped TCP segment like the WS path can), while key and button
+//! events go on a reliable ordered stream.
+//!
+//! Authenticated the same way as the WS handshake: the rustls certificate is
+//! derived from the node's ed25519 identity, and a custom
+//! `ServerCertVerifier` checks the peer's leaf certificate against the
+//! pinned device fingerprint from discovery/trust instead of a CA chain.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use quinn::{ClientConfig, Connection, Endpoint, RecvStream, SendStream, ServerConfig};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::DistinguishedName;
+
+use crate::identity::{fingerprint_of, Identity};
+use crate::protocol::InputEvent;
+use crate::transport::{is_loss_tolerant, Transport};
+use crate::trust::{self, DecisionSender};
+
+/// Self-signed certificate derived from the node's ed25519 identity keypair,
+/// so the same key that signs the WS handshake also authenticates QUIC.
+fn self_signed_cert(identity: &Identity) -> anyhow::Result<(CertificateDer<'static>, rustls::pki_types::PrivateKeyDer<'static>)> {
+    use pkcs8::EncodePrivateKey;
+    let pkcs8_der = identity.signing_key.to_pkcs8_der()?;
+    let keypair = rcgen::KeyPair::from_der(pkcs8_der.as_bytes())?;
+    let params = rcgen::CertificateParams::new(vec!["controll.local".into()])?;
+    let cert = params.self_signed(&keypair)?;
+    let key_der = rustls::pki_types::PrivatePkcs8KeyDer::from(pkcs8_der.as_bytes().to_vec());
+    Ok((cert.der().clone(), rustls::pki_types::PrivateKeyDer::Pkcs8(key_der)))
+}
+
+/// Verifies the peer's leaf certificate carries the ed25519 key whose
+/// fingerprint matches the one we expect (from `BEACON`/trust), rather than
+/// checking a CA chain. `expected: None` accepts any certificate that
+/// carries an ed25519 key; the fingerprint is not pinned or reported.
+#[derive(Debug)]
+struct PinnedFingerprintVerifier {
+    expected: Option<String>,
+}
+
+impl ServerCertVerifier for PinnedFingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = fingerprint_from_cert(end_entity)
+            .map_err(|_| rustls::Error::General("could not read ed25519 key from peer cert".into()))?;
+        if let Some(expected) = &self.expected {
+            if *expected != fingerprint {
+                return Err(rustls::Error::General(format!(
+                    "peer fingerprint {fingerprint} does not match pinned {expected}"
+                )));
+            }
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        // Proves the peer holds the private key behind the (pinned) certificate.
+        let provider = rustls::crypto::CryptoProvider::get_default()
+            .ok_or_else(|| rustls::Error::General("no rustls crypto provider installed".into()))?;
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        vec![rustls::SignatureScheme::ED25519]
+    }
+}
+
+/// Requires the client to present a certificate (so the session is mutually
+/// authenticated) but doesn't pin a fingerprint yet, mirroring
+/// `PinnedFingerprintVerifier { expected: None }` for the server side: the
+/// peer's fingerprint is read back out via `fingerprint_from_peer` after the
+/// handshake and run through the same trust-on-first-use gate as WS.
+#[derive(Debug)]
+struct AcceptAnyClientVerifier;
+
+impl ClientCertVerifier for AcceptAnyClientVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        fingerprint_from_cert(end_entity)
+            .map_err(|_| rustls::Error::General("could not read ed25519 key from peer cert".into()))?;
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        // Proves the peer holds the private key behind the (pinned) certificate.
+        let provider = rustls::crypto::CryptoProvider::get_default()
+            .ok_or_else(|| rustls::Error::General("no rustls crypto provider installed".into()))?;
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        vec![rustls::SignatureScheme::ED25519]
+    }
+}
+
+fn fingerprint_from_cert(cert: &CertificateDer<'_>) -> anyhow::Result<String> {
+    let (_, spki) = x509_parser::certificate::X509Certificate::from_der(cert.as_ref())
+        .map(|(rest, c)| (rest, c.public_key().subject_public_key.data.to_vec()))?;
+    let vk = ed25519_dalek::VerifyingKey::from_bytes(&spki.try_into().map_err(|_| anyhow::anyhow!("not an ed25519 key"))?)?;
+    Ok(fingerprint_of(&vk))
+}
+
+fn server_endpoint(addr: SocketAddr, identity: &Identity) -> anyhow::Result<Endpoint> {
+    let (cert, key) = self_signed_cert(identity)?;
+    let mut server_crypto = rustls::ServerConfig::builder()
+        .with_client_cert_verifier(Arc::new(AcceptAnyClientVerifier))
+        .with_single_cert(vec![cert], key)?;
+    server_crypto.alpn_protocols = vec![b"controll-quic".to_vec()];
+    let server_config = ServerConfig::with_crypto(Arc::new(
+        quinn::crypto::rustls::QuicServerConfig::try_from(server_crypto)?,
+    ));
+    let endpoint = Endpoint::server(server_config, addr)?;
+    Ok(endpoint)
+}
+
+fn client_endpoint(identity: &Identity, expected_fingerprint: Option<String>) -> anyhow::Result<Endpoint> {
+    let (cert, key) = self_signed_cert(identity)?;
+    let mut client_crypto = rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedFingerprintVerifier { expected: expected_fingerprint }))
+        .with_client_auth_cert(vec![cert], key)?;
+    client_crypto.alpn_protocols = vec![b"controll-quic".to_vec()];
+    let client_config = ClientConfig::new(Arc::new(quinn::crypto::rustls::QuicClientConfig::try_from(client_crypto)?));
+    let mut endpoint = Endpoint::client("0.0.0.0:0".parse()?)?;
+    endpoint.set_default_client_config(client_config);
+    Ok(endpoint)
+}
+
+pub struct QuicTransport {
+    connection: Connection,
+    reliable_send: SendStream,
+    /// Parsed frames from the reliable stream, produced by a task that owns
+    /// `reliable_recv` for the life of the connection (see
+    /// `spawn_reliable_reader`). `recv_event` selects against this channel
+    /// instead of calling `AsyncReadExt::read_exact` directly in a
+    /// `tokio::select!`: `read_exact` isn't cancellation-safe, so a dropped
+    /// in-progress read would permanently desync the length-prefixed framing
+    /// for the rest of the session. `mpsc::Receiver::recv` is
+    /// cancellation-safe, so losing the select race here just leaves the
+    /// frame buffered for next time.
+    reliable_frames: tokio::sync::mpsc::Receiver<anyhow::Result<InputEvent>>,
+}
+
+impl QuicTransport {
+    fn new(connection: Connection, reliable_send: SendStream, reliable_recv: RecvStream) -> Self {
+        Self { connection, reliable_send, reliable_frames: spawn_reliable_reader(reliable_recv) }
+    }
+}
+
+#[async_trait]
+impl Transport for QuicTransport {
+    async fn send_event(&mut self, ev: &InputEvent) -> anyhow::Result<()> {
+        let bytes = serde_json::to_vec(ev)?;
+        if is_loss_tolerant(ev) {
+            self.connection.send_datagram(bytes.into())?;
+        } else {
+            use tokio::io::AsyncWriteExt;
+            let len = (bytes.len() as u32).to_be_bytes();
+            self.reliable_send.write_all(&len).await?;
+            self.reliable_send.write_all(&bytes).await?;
+        }
+        Ok(())
+    }
+
+    async fn recv_event(&mut self) -> anyhow::Result<Option<InputEvent>> {
+        tokio::select! {
+            datagram = self.connection.read_datagram() => {
+                match datagram {
+                    Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+                    Err(_) => Ok(None),
+                }
+            }
+            frame = self.reliable_frames.recv() => {
+                match frame {
+                    Some(Ok(ev)) => Ok(Some(ev)),
+                    Some(Err(e)) => Err(e),
+                    None => Ok(None),
+                }
+            }
+        }
+    }
+}
+
+/// Owns `recv` for the life of the connection and pushes parsed
+/// length-prefixed frames into the returned channel, so a `read_exact` that
+/// gets dropped mid-read (e.g. by losing a `select!` race elsewhere) only
+/// ever happens inside this task's own loop, never torn out from under a
+/// caller that needs to resume framing state.
+fn spawn_reliable_reader(mut recv: RecvStream) -> tokio::sync::mpsc::Receiver<anyhow::Result<InputEvent>> {
+    let (tx, rx) = tokio::sync::mpsc::channel(32);
+    tokio::spawn(async move {
+        loop {
+            match read_reliable_frame(&mut recv).await {
+                Ok(Some(ev)) => {
+                    if tx.send(Ok(ev)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    break;
+                }
+            }
+        }
+    });
+    rx
+}
+
+async fn read_reliable_frame(recv: &mut RecvStream) -> anyhow::Result<Option<InputEvent>> {
+    use tokio::io::AsyncReadExt;
+    let mut len_buf = [0u8; 4];
+    if recv.read_exact(&mut len_buf).await.is_err() {
+        return Ok(None);
+    }
+    let len = u32::from_be_bytes(len_buf) as usize;
+    let mut buf = vec![0u8; len];
+    recv.read_exact(&mut buf).await?;
+    Ok(Some(serde_json::from_slice(&buf)?))
+}
+
+/// Accept QUIC control sessions, running each through the same
+/// trust-on-first-use gate as the WS path and applying its input locally.
+pub async fn run_quic_server(
+    addr: SocketAddr,
+    identity: Arc<Identity>,
+    trust_tx: Option<DecisionSender>,
+) -> anyhow::Result<()> {
+    let endpoint = server_endpoint(addr, &identity)?;
+    println!("[quic] server listening on {}", addr);
+    while let Some(incoming) = endpoint.accept().await {
+        let trust_tx = trust_tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_quic_conn(incoming, trust_tx).await {
+                eprintln!("[quic] conn error: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn handle_quic_conn(incoming: quinn::Incoming, trust_tx: Option<DecisionSender>) -> anyhow::Result<()> {
+    let connection = incoming.await?;
+    let fingerprint = fingerprint_from_peer(&connection)?;
+    let name = crate::discovery::name_for(&fingerprint).unwrap_or_else(|| fingerprint.clone());
+    let approved = trust::request_decision(fingerprint.clone(), name, trust_tx.as_ref()).await;
+    if !approved {
+        connection.close(1u32.into(), b"untrusted device");
+        return Ok(());
+    }
+    let (reliable_send, reliable_recv) = connection.accept_bi().await?;
+    let mut transport = QuicTransport::new(connection, reliable_send, reliable_recv);
+    let mut input = crate::input::InputState::default();
+    while let Some(ev) = transport.recv_event().await? {
+        input.apply(ev);
+    }
+    input.reset();
+    Ok(())
+}
+
+fn fingerprint_from_peer(connection: &Connection) -> anyhow::Result<String> {
+    let certs = connection
+        .peer_identity()
+        .and_then(|id| id.downcast::<Vec<CertificateDer<'static>>>().ok())
+        .ok_or_else(|| anyhow::anyhow!("no peer certificate presented"))?;
+    let leaf = certs.first().ok_or_else(|| anyhow::anyhow!("empty peer certificate chain"))?;
+    fingerprint_from_cert(leaf)
+}
+
+/// Connect to a remote device's QUIC endpoint and open the reliable stream
+/// used for keyboard/button events.
+pub async fn connect(
+    addr: SocketAddr,
+    identity: Arc<Identity>,
+    expected_fingerprint: Option<String>,
+) -> anyhow::Result<QuicTransport> {
+    let endpoint = client_endpoint(&identity, expected_fingerprint)?;
+    let connection = endpoint.connect(addr, "controll.local")?.await?;
+    let (reliable_send, reliable_recv) = connection.open_bi().await?;
+    Ok(QuicTransport::new(connection, reliable_send, reliable_recv))
+}
+
+/// Mirrors `ws::run_ws_client`: connect, then hand the session off to the
+/// shared capture loop so real input actually reaches the peer. When
+/// `expected_fingerprint` is `Some`, `connect`'s `PinnedFingerprintVerifier`
+/// rejects the TLS handshake itself if the peer's cert doesn't match — the
+/// initiator-side half of trust-on-first-use, mirroring the
+/// `trust::request_decision` check `handle_quic_conn` already does for
+/// whoever is being controlled.
+pub async fn run_quic_client(
+    addr: SocketAddr,
+    identity: Arc<Identity>,
+    relative: bool,
+    expected_fingerprint: Option<String>,
+) -> anyhow::Result<()> {
+    println!("[quic] connecting to {}", addr);
+    let transport = connect(addr, identity, expected_fingerprint).await?;
+    crate::input::run_capture_client(Box::new(transport), relative).await;
+    Ok(())
+}
@@ -0,0 +1,90 @@
+//! Pluggable transport for input events.
+//!
+//! The WS backend (`ws.rs`) and the QUIC backend (`quic.rs`) both implement
+//! this trait so the rest of the app doesn't care which one a session
+//! negotiated. Selection happens via the `REQUEST_CONTROL` `options.transport`
+//! field (`"ws"`, the default, or `"quic"`); supported transports are
+//! advertised in `BEACON` and kept in `DeviceInfo::transports`. The GUI's
+//! transport choice is not checked against that list.
+
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::protocol::InputEvent;
+
+pub const TRANSPORT_WS: &str = "ws";
+pub const TRANSPORT_QUIC: &str = "quic";
+
+/// What this instance asked for in a `REQUEST_CONTROL`, remembered long
+/// enough to act on once `RESPONSE_CONTROL` comes back accepted (the
+/// accept/response pair in `protocol.rs` doesn't echo the options back).
+#[derive(Clone)]
+struct RequestedSession {
+    transport: String,
+    relative: bool,
+}
+
+/// Keyed by target device fingerprint (or target IP, if the fingerprint
+/// isn't known yet) rather than a single global, so two in-flight requests
+/// to different devices can't clobber each other's transport/map choice.
+static LAST_REQUESTED: Lazy<Mutex<HashMap<String, RequestedSession>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub fn set_last_requested(key: &str, options: &serde_json::Value) {
+    let session = RequestedSession {
+        transport: transport_from_options(options).to_string(),
+        relative: relative_from_options(options),
+    };
+    LAST_REQUESTED.lock().unwrap().insert(key.to_string(), session);
+}
+
+pub fn last_requested(key: &str) -> String {
+    LAST_REQUESTED
+        .lock()
+        .unwrap()
+        .get(key)
+        .map(|s| s.transport.clone())
+        .unwrap_or_else(|| TRANSPORT_WS.to_string())
+}
+
+/// Whether the session requested for `key` should move the mouse in
+/// `options.map == "relative"` mode; defaults to relative when nothing was
+/// requested for `key`, the same default `relative_from_options` applies.
+pub fn last_requested_relative(key: &str) -> bool {
+    LAST_REQUESTED.lock().unwrap().get(key).map(|s| s.relative).unwrap_or(true)
+}
+
+/// All transports this build knows how to speak, in advertise order.
+pub fn supported_transports() -> Vec<String> {
+    vec![TRANSPORT_WS.to_string(), TRANSPORT_QUIC.to_string()]
+}
+
+pub fn transport_from_options(options: &serde_json::Value) -> &'static str {
+    match options.get("transport").and_then(|v| v.as_str()) {
+        Some(TRANSPORT_QUIC) => TRANSPORT_QUIC,
+        _ => TRANSPORT_WS,
+    }
+}
+
+/// Honors `options.map`: anything other than the literal `"absolute"` keeps
+/// the default relative-motion mapping (see `InputEvent::MouseMoveRel`).
+pub fn relative_from_options(options: &serde_json::Value) -> bool {
+    options.get("map").and_then(|v| v.as_str()) != Some("absolute")
+}
+
+/// Events that are fine to lose (superseded by the next one) vs. events that
+/// must arrive, in order, exactly once.
+pub fn is_loss_tolerant(ev: &InputEvent) -> bool {
+    matches!(
+        ev,
+        InputEvent::MouseMove { .. } | InputEvent::MouseMoveRel { .. } | InputEvent::Scroll { .. }
+    )
+}
+
+#[async_trait]
+pub trait Transport: Send {
+    async fn send_event(&mut self, ev: &InputEvent) -> anyhow::Result<()>;
+    /// Returns `Ok(None)` once the peer has cleanly closed the session.
+    async fn recv_event(&mut self) -> anyhow::Result<Option<InputEvent>>;
+}
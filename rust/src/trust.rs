@@ -0,0 +1,202 @@
+//! Trust-on-first-use store for peer device fingerprints.
+//!
+//! The first time a given ed25519 fingerprint is seen, `ws::handle_ws_conn`
+//! parks the connection and asks whoever is driving the process (the GUI, or
+//! nobody in headless CLI use) to accept or reject it via [`request_decision`].
+//! Accepted fingerprints are persisted to disk so future sessions from that
+//! device auto-authenticate without prompting again.
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio::sync::oneshot;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrustedDevice {
+    pub name: String,
+    pub fingerprint: String,
+    /// Last address a control session to this device was dialed at, shown in
+    /// the GUI's trusted-devices list. Recorded before each connection attempt,
+    /// so it is not proof that the attempt succeeded.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Skip the manual Accept/Decline prompt on future inbound connections.
+    /// Set whenever a fingerprint is trusted; the GUI can unpin it without
+    /// fully revoking the device.
+    #[serde(default = "default_auto_accept")]
+    pub auto_accept: bool,
+}
+
+fn default_auto_accept() -> bool {
+    true
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct TrustStoreFile {
+    devices: Vec<TrustedDevice>,
+}
+
+pub struct TrustStore {
+    devices: HashMap<String, TrustedDevice>,
+}
+
+impl TrustStore {
+    fn load() -> Self {
+        let devices = fs::read(store_path())
+            .ok()
+            .and_then(|b| serde_json::from_slice::<TrustStoreFile>(&b).ok())
+            .map(|f| f.devices.into_iter().map(|d| (d.fingerprint.clone(), d)).collect())
+            .unwrap_or_default();
+        Self { devices }
+    }
+
+    fn persist(&self) {
+        let file = TrustStoreFile { devices: self.devices.values().cloned().collect() };
+        if let Ok(json) = serde_json::to_vec_pretty(&file) {
+            if let Some(parent) = store_path().parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+            let _ = fs::write(store_path(), json);
+        }
+    }
+
+    pub fn is_trusted(&self, fingerprint: &str) -> bool {
+        self.devices.contains_key(fingerprint)
+    }
+
+    /// A trusted device that hasn't been unpinned still skips the prompt;
+    /// this is what `request_decision` checks instead of `is_trusted` alone.
+    pub fn auto_accepts(&self, fingerprint: &str) -> bool {
+        self.devices.get(fingerprint).map(|d| d.auto_accept).unwrap_or(false)
+    }
+
+    pub fn trust(&mut self, fingerprint: &str, name: &str) {
+        self.devices
+            .entry(fingerprint.to_string())
+            .and_modify(|d| d.auto_accept = true)
+            .or_insert_with(|| TrustedDevice {
+                name: name.to_string(),
+                fingerprint: fingerprint.to_string(),
+                endpoint: None,
+                auto_accept: true,
+            });
+        self.persist();
+    }
+
+    pub fn revoke(&mut self, fingerprint: &str) {
+        self.devices.remove(fingerprint);
+        self.persist();
+    }
+
+    pub fn rename(&mut self, fingerprint: &str, name: &str) {
+        if let Some(d) = self.devices.get_mut(fingerprint) {
+            d.name = name.to_string();
+            self.persist();
+        }
+    }
+
+    pub fn set_auto_accept(&mut self, fingerprint: &str, auto_accept: bool) {
+        if let Some(d) = self.devices.get_mut(fingerprint) {
+            d.auto_accept = auto_accept;
+            self.persist();
+        }
+    }
+
+    /// Record the address a session to this device is being dialed at. Only
+    /// devices already in the store are updated.
+    pub fn update_endpoint(&mut self, fingerprint: &str, endpoint: &str) {
+        if let Some(d) = self.devices.get_mut(fingerprint) {
+            d.endpoint = Some(endpoint.to_string());
+            self.persist();
+        }
+    }
+
+    pub fn list(&self) -> Vec<TrustedDevice> {
+        self.devices.values().cloned().collect()
+    }
+}
+
+fn data_dir() -> PathBuf {
+    if let Ok(p) = std::env::var("CONTROLL_HOME") {
+        return PathBuf::from(p);
+    }
+    if let Ok(home) = std::env::var("HOME") {
+        return PathBuf::from(home).join(".controll");
+    }
+    PathBuf::from(".controll")
+}
+
+fn store_path() -> PathBuf {
+    data_dir().join("trusted_devices.json")
+}
+
+pub static TRUST_STORE: Lazy<Mutex<TrustStore>> = Lazy::new(|| Mutex::new(TrustStore::load()));
+
+static PENDING: Lazy<Mutex<HashMap<String, oneshot::Sender<bool>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Queue of unknown-fingerprint prompts for the UI to drain (mirrors the
+/// `DiscEvent` channel pattern used for discovery events).
+pub type DecisionSender = std::sync::mpsc::Sender<(String, String)>;
+
+/// Called from `handle_ws_conn` and `handle_quic_conn` for every connecting
+/// peer. Returns `true` at once for a stored fingerprint with auto-accept
+/// set; otherwise blocks (async) until the UI calls [`decide`], or returns
+/// `false` if nobody is listening.
+pub async fn request_decision(fingerprint: String, name: String, ui_tx: Option<&DecisionSender>) -> bool {
+    if TRUST_STORE.lock().unwrap().auto_accepts(&fingerprint) {
+        return true;
+    }
+    let (tx, rx) = oneshot::channel();
+    PENDING.lock().unwrap().insert(fingerprint.clone(), tx);
+    match ui_tx {
+        Some(ui_tx) => {
+            if ui_tx.send((fingerprint.clone(), name.clone())).is_err() {
+                PENDING.lock().unwrap().remove(&fingerprint);
+                return false;
+            }
+        }
+        None => {
+            // Headless: nobody can approve, so refuse pairing rather than
+            // silently trusting an unseen device.
+            PENDING.lock().unwrap().remove(&fingerprint);
+            return false;
+        }
+    }
+    rx.await.unwrap_or(false)
+}
+
+/// Called from the UI thread once the user accepts or rejects a fingerprint
+/// surfaced via [`request_decision`].
+pub fn decide(fingerprint: &str, name: &str, accepted: bool) {
+    if accepted {
+        TRUST_STORE.lock().unwrap().trust(fingerprint, name);
+    }
+    if let Some(tx) = PENDING.lock().unwrap().remove(fingerprint) {
+        let _ = tx.send(accepted);
+    }
+}
+
+/// All devices currently in the trust store (accepted and not revoked), for
+/// the GUI's managed-devices list.
+pub fn list() -> Vec<TrustedDevice> {
+    TRUST_STORE.lock().unwrap().list()
+}
+
+pub fn revoke(fingerprint: &str) {
+    TRUST_STORE.lock().unwrap().revoke(fingerprint);
+}
+
+pub fn rename(fingerprint: &str, name: &str) {
+    TRUST_STORE.lock().unwrap().rename(fingerprint, name);
+}
+
+pub fn set_auto_accept(fingerprint: &str, auto_accept: bool) {
+    TRUST_STORE.lock().unwrap().set_auto_accept(fingerprint, auto_accept);
+}
+
+pub fn update_endpoint(fingerprint: &str, endpoint: &str) {
+    TRUST_STORE.lock().unwrap().update_endpoint(fingerprint, endpoint);
+}
@@ -0,0 +1,93 @@
+//! External reachability for WAN control sessions.
+//!
+//! Discovery otherwise assumes everyone is on the same multicast-reachable
+//! LAN (see `discovery.rs`). To be reachable from outside it, we try to get
+//! the gateway to map the WS/QUIC port via UPnP-IGD and report our public IP;
+//! if there's no UPnP-capable gateway (common on carrier-grade NAT, some
+//! routers with it disabled), we fall back to a STUN-style reflexive lookup
+//! so we at least learn the address a peer would see us connect from.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
+use std::time::Duration;
+
+const STUN_SERVER: &str = "stun.l.google.com:19302";
+
+/// Best external address to advertise in `BEACON`, or `None` if neither UPnP
+/// nor STUN could determine one (e.g. offline, symmetric NAT).
+pub async fn external_address(local_ip: Ipv4Addr, port: u16) -> Option<IpAddr> {
+    if let Some(ip) = try_upnp(local_ip, port).await {
+        println!("[upnp] mapped port {port}, external ip {ip}");
+        return Some(IpAddr::V4(ip));
+    }
+    match try_stun().await {
+        Some(ip) => {
+            println!("[upnp] no IGD gateway, STUN reflexive address {ip}");
+            Some(IpAddr::V4(ip))
+        }
+        None => None,
+    }
+}
+
+/// Maps `port` for both protocols: TCP for the WS listener, UDP for the QUIC
+/// listener bound to the same port number (`quic::run_quic_server`). Only the
+/// TCP mapping is required for reachability to succeed; the UDP one is best
+/// effort so QUIC isn't silently unreachable through a NAT that mapped TCP.
+async fn try_upnp(local_ip: Ipv4Addr, port: u16) -> Option<Ipv4Addr> {
+    let gateway = igd_next::aio::tokio::search_gateway(Default::default()).await.ok()?;
+    let local_addr = SocketAddrV4::new(local_ip, port);
+    gateway
+        .add_port(igd_next::PortMappingProtocol::TCP, port, local_addr, 0, "controll")
+        .await
+        .ok()?;
+    if let Err(e) = gateway
+        .add_port(igd_next::PortMappingProtocol::UDP, port, local_addr, 0, "controll-quic")
+        .await
+    {
+        eprintln!("[upnp] UDP mapping for QUIC failed (TCP/WS still mapped): {e}");
+    }
+    gateway.get_external_ip().await.ok()
+}
+
+/// Minimal RFC 5389 STUN binding request/response, just enough to pull the
+/// XOR-MAPPED-ADDRESS attribute out of the reply.
+async fn try_stun() -> Option<Ipv4Addr> {
+    tokio::task::spawn_blocking(|| {
+        let sock = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+        sock.set_read_timeout(Some(Duration::from_secs(2))).ok()?;
+        sock.connect(STUN_SERVER).ok()?;
+
+        let mut req = [0u8; 20];
+        req[0..2].copy_from_slice(&0x0001u16.to_be_bytes()); // Binding Request
+        req[2..4].copy_from_slice(&0u16.to_be_bytes()); // message length, no attributes
+        req[4..8].copy_from_slice(&0x2112_A442u32.to_be_bytes()); // magic cookie
+        let txn_id: [u8; 12] = std::array::from_fn(|_| rand::random());
+        req[8..20].copy_from_slice(&txn_id);
+        sock.send(&req).ok()?;
+
+        let mut buf = [0u8; 512];
+        let n = sock.recv(&mut buf).ok()?;
+        parse_xor_mapped_address(&buf[..n], &txn_id)
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+fn parse_xor_mapped_address(resp: &[u8], txn_id: &[u8; 12]) -> Option<Ipv4Addr> {
+    if resp.len() < 20 || &resp[8..20] != txn_id {
+        return None;
+    }
+    let mut offset = 20;
+    while offset + 4 <= resp.len() {
+        let attr_type = u16::from_be_bytes([resp[offset], resp[offset + 1]]);
+        let attr_len = u16::from_be_bytes([resp[offset + 2], resp[offset + 3]]) as usize;
+        let value = resp.get(offset + 4..offset + 4 + attr_len)?;
+        // XOR-MAPPED-ADDRESS (0x0020); ignore the older non-XOR MAPPED-ADDRESS.
+        if attr_type == 0x0020 && value.len() >= 8 && value[1] == 0x01 {
+            let xaddr = [value[4] ^ 0x21, value[5] ^ 0x12, value[6] ^ 0xA4, value[7] ^ 0x42];
+            return Some(Ipv4Addr::from(xaddr));
+        }
+        offset += 4 + attr_len + (4 - attr_len % 4) % 4; // attributes are padded to a 4-byte boundary
+    }
+    None
+}
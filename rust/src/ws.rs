@@ -1,52 +1,152 @@
+use async_trait::async_trait;
 use futures::{SinkExt, StreamExt};
 use tokio::net::{TcpListener, TcpStream};
-use tokio_tungstenite::{accept_async, connect_async, tungstenite::protocol::Message as WsMsg};
-use serde_json::Value;
-use enigo::{Enigo, Mouse, Settings, Coordinate};
+use tokio_tungstenite::{accept_async, connect_async, tungstenite::protocol::Message as WsMsg, WebSocketStream};
+use std::sync::Arc;
 
-pub async fn run_ws_server(host: &str, port: u16) -> anyhow::Result<()> {
+use crate::crypto::{self, Role, SecureChannel};
+use crate::identity::Identity;
+use crate::input::InputState;
+use crate::protocol::InputEvent;
+use crate::transport::Transport;
+use crate::trust::{self, DecisionSender};
+
+/// WS backend for the `Transport` trait: input events are JSON-encoded,
+/// sealed with the session's `SecureChannel`, and carried as binary frames.
+pub struct WsTransport<T> {
+    ws: WebSocketStream<T>,
+    channel: SecureChannel,
+}
+
+#[async_trait]
+impl<T> Transport for WsTransport<T>
+where
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send,
+{
+    async fn send_event(&mut self, ev: &InputEvent) -> anyhow::Result<()> {
+        let pt = serde_json::to_vec(ev)?;
+        let ct = self.channel.encrypt(&pt)?;
+        self.ws.send(WsMsg::Binary(ct)).await?;
+        Ok(())
+    }
+
+    async fn recv_event(&mut self) -> anyhow::Result<Option<InputEvent>> {
+        loop {
+            match self.ws.next().await {
+                Some(Ok(WsMsg::Binary(ct))) => {
+                    let pt = self.channel.decrypt(&ct)?;
+                    return Ok(Some(serde_json::from_slice(&pt)?));
+                }
+                Some(Ok(WsMsg::Close(_))) | None => return Ok(None),
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+pub async fn run_ws_server(
+    host: &str,
+    port: u16,
+    identity: Arc<Identity>,
+    trust_tx: Option<DecisionSender>,
+) -> anyhow::Result<()> {
     let addr = format!("{}:{}", host, port);
     let listener = TcpListener::bind(&addr).await?;
     println!("[ws] server listening on ws://{}", addr);
     loop {
         let (stream, peer) = listener.accept().await?;
         println!("[ws] tcp accepted from {}", peer);
+        let identity = identity.clone();
+        let trust_tx = trust_tx.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_ws_conn(stream).await {
+            if let Err(e) = handle_ws_conn(stream, identity, trust_tx).await {
                 eprintln!("[ws] conn error: {e}");
             }
         });
     }
 }
 
-async fn handle_ws_conn(stream: TcpStream) -> anyhow::Result<()> {
-    let mut ws = accept_async(stream).await?;
-    while let Some(msg) = ws.next().await {
-        match msg {
-            Ok(WsMsg::Text(t)) => {
-                if let Ok(v) = serde_json::from_str::<Value>(&t) {
-                    if v.get("type").and_then(|s| s.as_str()) == Some("mouse_move") {
-                        let x = v.get("x").and_then(|n| n.as_i64()).unwrap_or(0) as i32;
-                        let y = v.get("y").and_then(|n| n.as_i64()).unwrap_or(0) as i32;
-                        // Create Enigo inside the per-message scope so it doesn't cross an await
-                        if let Ok(mut enigo) = Enigo::new(&Settings::default()) {
-                            let _ = enigo.move_mouse(x, y, Coordinate::Abs);
-                        }
-                    }
-                }
+/// Run the mutual ed25519+X25519 handshake over a freshly-accepted/connected
+/// WS stream and derive the session's `SecureChannel`.
+async fn do_handshake<T>(
+    ws: &mut WebSocketStream<T>,
+    role: Role,
+    identity: &Identity,
+) -> anyhow::Result<SecureChannel>
+where
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    crypto::handshake(
+        role,
+        &identity.signing_key,
+        |bytes| async { Ok(ws.send(WsMsg::Text(String::from_utf8(bytes)?)).await?) },
+        || async {
+            match ws.next().await {
+                Some(Ok(WsMsg::Text(t))) => Ok(t.into_bytes()),
+                Some(Ok(_)) => anyhow::bail!("expected handshake frame, got a different message type"),
+                Some(Err(e)) => Err(e.into()),
+                None => anyhow::bail!("connection closed during handshake"),
             }
-            Ok(WsMsg::Close(_)) => break,
-            Ok(_) => {}
-            Err(e) => { eprintln!("[ws] recv err: {e}"); break; }
-        }
+        },
+    )
+    .await
+}
+
+async fn handle_ws_conn(
+    stream: TcpStream,
+    identity: Arc<Identity>,
+    trust_tx: Option<DecisionSender>,
+) -> anyhow::Result<()> {
+    let mut ws = accept_async(stream).await?;
+    let channel = do_handshake(&mut ws, Role::Responder, &identity).await?;
+    println!("[ws] handshake ok, peer fingerprint {}", channel.peer_fingerprint);
+
+    let name = crate::discovery::name_for(&channel.peer_fingerprint)
+        .unwrap_or_else(|| channel.peer_fingerprint.clone());
+    let approved = trust::request_decision(channel.peer_fingerprint.clone(), name, trust_tx.as_ref()).await;
+    if !approved {
+        println!("[ws] rejecting untrusted device {}", channel.peer_fingerprint);
+        let _ = ws.close(None).await;
+        return Ok(());
+    }
+    println!("[ws] session established with {}", channel.peer_fingerprint);
+
+    let mut transport = WsTransport { ws, channel };
+    let mut input = InputState::default();
+    while let Some(ev) = transport.recv_event().await? {
+        input.apply(ev);
     }
+    input.reset();
     Ok(())
 }
 
-pub async fn run_ws_client(url: &str) -> anyhow::Result<()> {
+/// Connects and runs the capture loop against `url`. When `expected_fingerprint`
+/// is `Some`, the peer's handshake fingerprint is checked against it before any
+/// input is sent — this is the initiator-side half of trust-on-first-use;
+/// `handle_ws_conn` already does the equivalent check (via `trust::request_decision`)
+/// for whoever is being controlled. Without this, a spoofed discovery beacon or
+/// redirected address could silently receive the live input stream instead of
+/// the device the caller actually asked for.
+pub async fn run_ws_client(
+    url: &str,
+    identity: Arc<Identity>,
+    relative: bool,
+    expected_fingerprint: Option<String>,
+) -> anyhow::Result<()> {
     println!("[ws] connecting to {}", url);
     let (mut ws, _resp) = connect_async(url).await?;
-    ws.send(WsMsg::Text("ping".into())).await.ok();
-    if let Some(Ok(msg)) = ws.next().await { println!("[ws] got: {:?}", msg); }
+    let channel = do_handshake(&mut ws, Role::Initiator, &identity).await?;
+    println!("[ws] handshake ok, peer fingerprint {}", channel.peer_fingerprint);
+    if let Some(expected) = &expected_fingerprint {
+        if *expected != channel.peer_fingerprint {
+            anyhow::bail!(
+                "peer fingerprint {} does not match expected {expected}",
+                channel.peer_fingerprint
+            );
+        }
+    }
+    let transport = WsTransport { ws, channel };
+    crate::input::run_capture_client(Box::new(transport), relative).await;
     Ok(())
 }